@@ -0,0 +1,191 @@
+use eyre::bail;
+use std::fmt;
+use std::str::FromStr;
+
+/// A BLAKE2 variant that can be selected with `--algorithm`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Blake2b,
+    Blake2bp,
+    Blake2s,
+    Blake2sp,
+}
+
+impl HashAlgorithm {
+    /// The label used in BSD-style `--tag` output and recognized when
+    /// parsing BSD-style checksum lines.
+    pub fn label(self) -> &'static str {
+        match self {
+            HashAlgorithm::Blake2b => "BLAKE2b",
+            HashAlgorithm::Blake2bp => "BLAKE2bp",
+            HashAlgorithm::Blake2s => "BLAKE2s",
+            HashAlgorithm::Blake2sp => "BLAKE2sp",
+        }
+    }
+
+    /// The maximum digest length, in bytes, supported by this variant.
+    pub fn max_length(self) -> usize {
+        match self {
+            HashAlgorithm::Blake2b | HashAlgorithm::Blake2bp => 64,
+            HashAlgorithm::Blake2s | HashAlgorithm::Blake2sp => 32,
+        }
+    }
+
+    /// The maximum secret key length, in bytes, accepted by `--key-file` /
+    /// `--key-string` for this variant.
+    pub fn max_key_length(self) -> usize {
+        self.max_length()
+    }
+
+    /// Build a boxed digest state for this variant, ready to receive
+    /// `update`s. When `key` is present the digest becomes a keyed MAC.
+    pub fn new_state(self, length: usize, key: Option<&[u8]>) -> Box<dyn DigestState> {
+        match self {
+            HashAlgorithm::Blake2b => {
+                let mut params = blake2b_simd::Params::new();
+                params.hash_length(length);
+                if let Some(key) = key {
+                    params.key(key);
+                }
+
+                Box::new(params.to_state())
+            }
+            HashAlgorithm::Blake2bp => {
+                let mut params = blake2b_simd::blake2bp::Params::new();
+                params.hash_length(length);
+                if let Some(key) = key {
+                    params.key(key);
+                }
+
+                Box::new(params.to_state())
+            }
+            HashAlgorithm::Blake2s => {
+                let mut params = blake2s_simd::Params::new();
+                params.hash_length(length);
+                if let Some(key) = key {
+                    params.key(key);
+                }
+
+                Box::new(params.to_state())
+            }
+            HashAlgorithm::Blake2sp => {
+                let mut params = blake2s_simd::blake2sp::Params::new();
+                params.hash_length(length);
+                if let Some(key) = key {
+                    params.key(key);
+                }
+
+                Box::new(params.to_state())
+            }
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.label())
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> eyre::Result<Self> {
+        match s {
+            "blake2b" => Ok(HashAlgorithm::Blake2b),
+            "blake2bp" => Ok(HashAlgorithm::Blake2bp),
+            "blake2s" => Ok(HashAlgorithm::Blake2s),
+            "blake2sp" => Ok(HashAlgorithm::Blake2sp),
+            other => bail!("Unknown algorithm: {}", other),
+        }
+    }
+}
+
+/// A running digest that can be fed bytes and finalized to the raw digest
+/// bytes, regardless of which BLAKE2 variant backs it.
+pub trait DigestState {
+    fn update(&mut self, input: &[u8]);
+    fn finalize(self: Box<Self>) -> Vec<u8>;
+}
+
+impl DigestState for blake2b_simd::State {
+    fn update(&mut self, input: &[u8]) {
+        blake2b_simd::State::update(self, input);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        blake2b_simd::State::finalize(&self).as_bytes().to_vec()
+    }
+}
+
+impl DigestState for blake2b_simd::blake2bp::State {
+    fn update(&mut self, input: &[u8]) {
+        blake2b_simd::blake2bp::State::update(self, input);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        blake2b_simd::blake2bp::State::finalize(&self).as_bytes().to_vec()
+    }
+}
+
+impl DigestState for blake2s_simd::State {
+    fn update(&mut self, input: &[u8]) {
+        blake2s_simd::State::update(self, input);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        blake2s_simd::State::finalize(&self).as_bytes().to_vec()
+    }
+}
+
+impl DigestState for blake2s_simd::blake2sp::State {
+    fn update(&mut self, input: &[u8]) {
+        blake2s_simd::blake2sp::State::update(self, input);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        blake2s_simd::blake2sp::State::finalize(&self).as_bytes().to_vec()
+    }
+}
+
+/// Render a digest in lowercase hex.
+pub fn to_hex(digest: &[u8]) -> String {
+    let mut out = String::with_capacity(digest.len() * 2);
+    for byte in digest {
+        out.push_str(&format!("{:02x}", byte));
+    }
+
+    out
+}
+
+/// Render a digest as standard, padded base64.
+pub fn to_base64(digest: &[u8]) -> String {
+    data_encoding::BASE64.encode(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_known_algorithms() {
+        assert_eq!(HashAlgorithm::Blake2b, "blake2b".parse().unwrap());
+        assert_eq!(HashAlgorithm::Blake2bp, "blake2bp".parse().unwrap());
+        assert_eq!(HashAlgorithm::Blake2s, "blake2s".parse().unwrap());
+        assert_eq!(HashAlgorithm::Blake2sp, "blake2sp".parse().unwrap());
+    }
+
+    #[test]
+    fn rejects_unknown_algorithm() {
+        let result: eyre::Result<HashAlgorithm> = "blake3".parse();
+        assert_eq!("Unknown algorithm: blake3", result.unwrap_err().to_string());
+    }
+
+    #[test]
+    fn max_length_matches_digest_size() {
+        assert_eq!(64, HashAlgorithm::Blake2b.max_length());
+        assert_eq!(64, HashAlgorithm::Blake2bp.max_length());
+        assert_eq!(32, HashAlgorithm::Blake2s.max_length());
+        assert_eq!(32, HashAlgorithm::Blake2sp.max_length());
+    }
+}
@@ -0,0 +1,254 @@
+use crate::algorithm::HashAlgorithm;
+use eyre::bail;
+use regex::bytes::Regex;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::sync::OnceLock;
+
+fn gnu_line_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"(?-u)^(\\)?([0-9A-Za-z+/=]+) ([ *])(.*)$").unwrap())
+}
+
+fn bsd_line_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"(?s-u)^(\\)?([A-Za-z0-9]+)(?:-keyed)?(?:-([0-9]+))? \((.*)\) = ([0-9A-Za-z+/=]+)$").unwrap())
+}
+
+/// Decode a checksum token, auto-detecting whether it's lowercase/uppercase
+/// hex or standard base64.
+pub fn decode_digest(token: &[u8]) -> eyre::Result<Vec<u8>> {
+    if token.len() >= 2 && token.len().is_multiple_of(2) && token.iter().all(u8::is_ascii_hexdigit) {
+        return Ok(decode_hex(token));
+    }
+
+    data_encoding::BASE64
+        .decode(token)
+        .map_err(|e| eyre::eyre!("Invalid digest encoding: {}", e))
+}
+
+fn decode_hex(token: &[u8]) -> Vec<u8> {
+    token
+        .chunks(2)
+        .map(|pair| {
+            let hi = (pair[0] as char).to_digit(16).expect("checked ascii hex digit") as u8;
+            let lo = (pair[1] as char).to_digit(16).expect("checked ascii hex digit") as u8;
+            (hi << 4) | lo
+        })
+        .collect()
+}
+
+/// Trim leading and trailing ASCII whitespace, mirroring `str::trim` but
+/// operating on raw bytes so non-UTF-8 filenames survive untouched.
+pub fn trim_bytes(input: &[u8]) -> &[u8] {
+    let start = input.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(input.len());
+    let end = input.iter().rposition(|b| !b.is_ascii_whitespace()).map_or(start, |p| p + 1);
+    &input[start..end]
+}
+
+/// Split a checksum line into the raw digest bytes (the hash token may be
+/// hex or base64, auto-detected) and the filename. Recognizes both the GNU
+/// `[\]HASH  filename` / `[\]HASH *filename` layout and the BSD
+/// `[\]ALG (filename) = HASH` / `[\]ALG-NNN (filename) = HASH` layout, which
+/// must name `algorithm` and may carry a `-keyed` marker right after `ALG`,
+/// as `--tag` writes for keyed digests. In both layouts a leading `\` marks `filename` as
+/// backslash-escaped and is decoded back to the real name.
+pub fn split_check_line(line: &[u8], algorithm: HashAlgorithm) -> eyre::Result<(Vec<u8>, OsString)> {
+    if let Some(captures) = gnu_line_regex().captures(line) {
+        let hash_bytes = &captures[2];
+        if hash_bytes.iter().all(u8::is_ascii_hexdigit) && (hash_bytes.len() < 2 || hash_bytes.len() % 2 != 0) {
+            bail!("Invalid hash length: {}", hash_bytes.len());
+        }
+
+        let hash = decode_digest(hash_bytes)?;
+        validate_digest_length(&hash, algorithm)?;
+        let raw_filename = &captures[4];
+        if raw_filename.is_empty() {
+            bail!("Malformed line");
+        }
+
+        let filename = if captures.get(1).is_some() {
+            OsString::from_vec(unescape_filename(raw_filename))
+        } else {
+            OsString::from_vec(raw_filename.to_vec())
+        };
+
+        return Ok((hash, filename));
+    }
+
+    if let Some(captures) = bsd_line_regex().captures(line) {
+        let label = std::str::from_utf8(&captures[2]).expect("regex only matches ASCII");
+        if label != algorithm.label() {
+            bail!("Checksum line names {}, not {}", label, algorithm.label());
+        }
+
+        let hash_bytes = &captures[5];
+        let hash = decode_digest(hash_bytes)?;
+        validate_digest_length(&hash, algorithm)?;
+        let raw_filename = &captures[4];
+        let filename = if captures.get(1).is_some() {
+            OsString::from_vec(unescape_filename(raw_filename))
+        } else {
+            OsString::from_vec(raw_filename.to_vec())
+        };
+
+        return Ok((hash, filename));
+    }
+
+    bail!("Malformed line")
+}
+
+/// Reject digests that are empty or larger than `algorithm` can produce,
+/// so an oversized check-file token is reported as a malformed line
+/// instead of reaching `HashAlgorithm::new_state` and panicking.
+fn validate_digest_length(hash: &[u8], algorithm: HashAlgorithm) -> eyre::Result<()> {
+    if hash.is_empty() || hash.len() > algorithm.max_length() {
+        bail!("Invalid hash length: {}", hash.len());
+    }
+
+    Ok(())
+}
+
+fn unescape_filename(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len());
+    let mut bytes = input.iter().copied();
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            output.push(b);
+            continue;
+        }
+
+        match bytes.next() {
+            Some(b'n') => output.push(b'\n'),
+            Some(b'\\') => output.push(b'\\'),
+            Some(other) => {
+                output.push(b'\\');
+                output.push(other);
+            }
+            None => output.push(b'\\'),
+        }
+    }
+
+    output
+}
+
+/// Escape a filename for output. Returns the escaped bytes and whether the
+/// GNU leading-backslash marker is required, so the caller can print it
+/// ahead of the hash.
+pub fn escape_filename(name: &OsStr) -> (bool, Vec<u8>) {
+    let bytes = name.as_bytes();
+    if !bytes.contains(&b'\\') && !bytes.contains(&b'\n') {
+        return (false, bytes.to_vec());
+    }
+
+    let mut output = Vec::with_capacity(bytes.len());
+    for &b in bytes {
+        match b {
+            b'\\' => output.extend_from_slice(b"\\\\"),
+            b'\n' => output.extend_from_slice(b"\\n"),
+            _ => output.push(b),
+        }
+    }
+
+    (true, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_check_line_with_valid_hex_line() {
+        let line = b"c0ae24f806df19d850565b234bc37afd5035e7536388290db9413c98578394313f38b093143ecfbc208425d54b9bfef0d9917a9e93910f7914a97e73fea23534  test";
+        let (hash, filename) = split_check_line(line, HashAlgorithm::Blake2b).unwrap();
+        assert_eq!(&[0xc0, 0xae, 0x24, 0xf8], &hash[..4]);
+        assert_eq!(OsString::from("test"), filename);
+    }
+
+    #[test]
+    fn split_check_line_with_binary_marker() {
+        let line = b"c0ae24f806df19d850565b234bc37afd5035e7536388290db9413c98578394313f38b093143ecfbc208425d54b9bfef0d9917a9e93910f7914a97e73fea23534 *test";
+        let (_, filename) = split_check_line(line, HashAlgorithm::Blake2b).unwrap();
+        assert_eq!(OsString::from("test"), filename);
+    }
+
+    #[test]
+    fn split_check_line_with_base64_line() {
+        let digest = decode_hex(b"c0ae24f806df19d850565b234bc37afd5035e7536388290db9413c98578394313f38b093143ecfbc208425d54b9bfef0d9917a9e93910f7914a97e73fea23534");
+        let encoded = data_encoding::BASE64.encode(&digest);
+        let mut line = encoded.into_bytes();
+        line.extend_from_slice(b"  test");
+
+        let (hash, filename) = split_check_line(&line, HashAlgorithm::Blake2b).unwrap();
+        assert_eq!(digest, hash);
+        assert_eq!(OsString::from("test"), filename);
+    }
+
+    #[test]
+    fn split_check_line_with_bsd_tag_line() {
+        let line = b"BLAKE2b (test) = c0ae";
+        let (hash, filename) = split_check_line(line, HashAlgorithm::Blake2b).unwrap();
+        assert_eq!(vec![0xc0, 0xae], hash);
+        assert_eq!(OsString::from("test"), filename);
+    }
+
+    #[test]
+    fn split_check_line_with_bsd_tag_line_wrong_algorithm() {
+        let line = b"BLAKE2s (test) = c0ae";
+        let result = split_check_line(line, HashAlgorithm::Blake2b).unwrap_err();
+        assert_eq!("Checksum line names BLAKE2s, not BLAKE2b", result.to_string());
+    }
+
+    #[test]
+    fn split_check_line_with_bsd_tag_line_round_trips_escaped_filename() {
+        let (needs_escape, escaped) = escape_filename(OsStr::new("weird\\name\nhere"));
+        assert!(needs_escape);
+
+        let mut line = Vec::new();
+        line.extend_from_slice(b"\\BLAKE2b (");
+        line.extend_from_slice(&escaped);
+        line.extend_from_slice(b") = c0ae");
+
+        let (hash, filename) = split_check_line(&line, HashAlgorithm::Blake2b).unwrap();
+        assert_eq!(vec![0xc0, 0xae], hash);
+        assert_eq!(OsString::from("weird\\name\nhere"), filename);
+    }
+
+    #[test]
+    fn split_check_line_with_missing_filename() {
+        let line = b"c0ae24f806df19d850565b234bc37afd5035e7536388290db9413c98578394313f38b093143ecfbc208425d54b9bfef0d9917a9e93910f7914a97e73fea23534  ";
+        let result = split_check_line(line, HashAlgorithm::Blake2b).unwrap_err();
+        assert_eq!("Malformed line", result.to_string());
+    }
+
+    #[test]
+    fn split_check_line_with_too_small_hash() {
+        let line = b"c  test";
+        let result = split_check_line(line, HashAlgorithm::Blake2b).unwrap_err();
+        assert_eq!("Invalid hash length: 1", result.to_string());
+    }
+
+    #[test]
+    fn split_check_line_with_too_long_hash() {
+        let hash = "a".repeat(130);
+        let line = format!("{}  test", hash);
+        let result = split_check_line(line.as_bytes(), HashAlgorithm::Blake2b).unwrap_err();
+        assert_eq!("Invalid hash length: 65", result.to_string());
+    }
+
+    #[test]
+    fn split_check_line_round_trips_escaped_filename() {
+        let (needs_escape, escaped) = escape_filename(OsStr::new("weird\\name\nhere"));
+        assert!(needs_escape);
+
+        let mut line = Vec::new();
+        line.extend_from_slice(b"\\");
+        line.extend_from_slice(b"c0");
+        line.extend_from_slice(b"  ");
+        line.extend_from_slice(&escaped);
+
+        let (hash, filename) = split_check_line(&line, HashAlgorithm::Blake2b).unwrap();
+        assert_eq!(vec![0xc0], hash);
+        assert_eq!(OsString::from("weird\\name\nhere"), filename);
+    }
+}
@@ -1,13 +1,25 @@
+mod algorithm;
+mod checkline;
+
+use algorithm::HashAlgorithm;
+use checkline::{escape_filename, split_check_line, trim_bytes};
 use docopt::Docopt;
 use eyre::bail;
+use rayon::prelude::*;
 use serde::Deserialize;
+use std::ffi::OsStr;
 use std::fs::File;
 use std::io::{self, BufRead, BufReader};
 use std::path::Path;
 use std::process;
 
+/// Files at or above this size are hashed through a memory map instead of
+/// the buffered streaming reader, mirroring the approach b3sum takes for
+/// throughput on large files.
+const MMAP_MIN_SIZE: u64 = 16 * 1024;
+
 const USAGE: &str = "
-Print or check BLAKE2 (512-bit) checksums.
+Print or check BLAKE2 checksums.
 With no FILE, or when FILE is -, read standard input.
 
 Usage:
@@ -16,10 +28,27 @@ Usage:
   b2sum --version
 
 Options:
+  -a, --algorithm=ALG   hash algorithm to use: blake2b, blake2bp, blake2s,
+                        blake2sp [default: blake2b]
+  -b, --binary          read/write in binary mode, marking the checksum line
+                        with a `*`
+      --base64          emit base64-encoded digests instead of hex
   -c, --check           read BLAKE2 sums from the FILEs and check them
+      --key-file=FILE   use the raw bytes of FILE as a secret key, turning
+                        the digest into a keyed MAC (64 bytes max for blake2b
+                        and blake2bp, 32 for blake2s and blake2sp)
+      --key-string=KEY  use KEY as a secret key, turning the digest into a
+                        keyed MAC (64 bytes max for blake2b and blake2bp, 32
+                        for blake2s and blake2sp)
   -l, --length=BITS     digest length in bits; must not exceed the maximum for the
-                        blake2 algorithm and must be a multiple of 8 [default: 512]
+                        blake2 algorithm and must be a multiple of 8 [default: the
+                        algorithm's maximum]
+      --no-mmap         always use the streaming reader instead of memory-mapping files
+      --num-threads=N   number of threads to hash multiple files with; 0 means
+                        let rayon pick [default: 0]
       --tag             create a BSD-style checksum
+  -t, --text            read/write in text mode (default), marking the
+                        checksum line with a space
 
 The following five options are useful only when verifying checksums:
       --ignore-missing  don't fail or report status for missing files
@@ -39,13 +68,21 @@ a line with checksum and name for each FILE.
 #[derive(Debug, Deserialize)]
 struct Args {
     arg_filename: Vec<String>,
+    flag_algorithm: String,
+    flag_base64: bool,
+    flag_binary: bool,
     flag_check: bool,
     flag_ignore_missing: bool,
-    flag_length: usize,
+    flag_key_file: Option<String>,
+    flag_key_string: Option<String>,
+    flag_length: Option<usize>,
+    flag_no_mmap: bool,
+    flag_num_threads: usize,
     flag_quiet: bool,
     flag_status: bool,
     flag_strict: bool,
     flag_tag: bool,
+    flag_text: bool,
     flag_version: bool,
     flag_warn: bool,
 }
@@ -56,11 +93,58 @@ fn print_version() -> ! {
     process::exit(0)
 }
 
-fn hash_reader<R>(length: usize, mut reader: R) -> eyre::Result<String>
+/// Resolve the digest length, in bytes, that should be used for `algorithm`.
+/// When `--length` isn't given, that's the algorithm's own maximum; when it
+/// is given, it must be a multiple of 8 and must not exceed that maximum.
+fn digest_length(algorithm: HashAlgorithm, flag_length: Option<usize>) -> eyre::Result<usize> {
+    let max_length = algorithm.max_length();
+    let flag_length = match flag_length {
+        Some(flag_length) => flag_length,
+        None => return Ok(max_length),
+    };
+
+    if flag_length % 8 != 0 {
+        bail!("Invalid length: {} bits is not a multiple of 8", flag_length);
+    }
+
+    let length = flag_length / 8;
+    if length > max_length {
+        bail!(
+            "Invalid length: {} bits exceeds the maximum of {} bits for {}",
+            flag_length,
+            max_length * 8,
+            algorithm
+        );
+    }
+
+    Ok(length)
+}
+
+/// Read the key bytes requested via `--key-file`/`--key-string`, if any,
+/// rejecting keys longer than `algorithm` supports.
+fn resolve_key(args: &Args, algorithm: HashAlgorithm) -> eyre::Result<Option<Vec<u8>>> {
+    let key = if let Some(key_string) = &args.flag_key_string {
+        Some(key_string.as_bytes().to_vec())
+    } else if let Some(path) = &args.flag_key_file {
+        Some(std::fs::read(path)?)
+    } else {
+        None
+    };
+
+    if let Some(key) = &key {
+        if key.len() > algorithm.max_key_length() {
+            bail!("Key must not exceed {} bytes", algorithm.max_key_length());
+        }
+    }
+
+    Ok(key)
+}
+
+fn hash_reader<R>(algorithm: HashAlgorithm, length: usize, key: Option<&[u8]>, mut reader: R) -> eyre::Result<Vec<u8>>
 where
     R: BufRead,
 {
-    let mut digest = blake2b_simd::Params::new().hash_length(length).to_state();
+    let mut digest = algorithm.new_state(length, key);
 
     loop {
         let count = {
@@ -76,80 +160,133 @@ where
         reader.consume(count);
     }
 
-    let output = digest.finalize();
-    let result = output.to_hex().to_ascii_lowercase();
-
-    Ok(result)
+    Ok(digest.finalize())
 }
 
-fn hash_file<P>(length: usize, path: P) -> eyre::Result<String>
+fn hash_file<P>(algorithm: HashAlgorithm, length: usize, key: Option<&[u8]>, path: P) -> eyre::Result<Vec<u8>>
 where
     P: AsRef<Path>,
 {
     let file = File::open(path)?;
     let reader = BufReader::new(file);
-    hash_reader(length, reader)
+    hash_reader(algorithm, length, key, reader)
 }
 
-fn split_check_line(line: &str) -> eyre::Result<(&str, &str)> {
-    let hash_length = line.chars().position(|c| !c.is_digit(16)).unwrap_or(0);
-    if hash_length < 2 || hash_length % 2 != 0 || hash_length > 128 {
-        bail!("Invalid hash length: {}", hash_length);
+/// Memory-map `file` and hash it in one shot, if it's a regular file large
+/// enough for mapping to pay off. Returns `None` when the streaming reader
+/// should be used instead (stdin, pipes, small or empty files).
+fn try_hash_mmap(algorithm: HashAlgorithm, length: usize, key: Option<&[u8]>, file: &File) -> eyre::Result<Option<Vec<u8>>> {
+    let metadata = file.metadata()?;
+    if !metadata.is_file() || metadata.len() < MMAP_MIN_SIZE {
+        return Ok(None);
     }
 
-    let hash = &line[0..hash_length];
-    let line = &line[hash_length..];
-    if line.len() < 3 {
-        bail!("Malformed line");
+    let mmap = unsafe { memmap2::Mmap::map(file)? };
+    let mut digest = algorithm.new_state(length, key);
+    digest.update(&mmap);
+    Ok(Some(digest.finalize()))
+}
+
+fn hash_path<P>(algorithm: HashAlgorithm, length: usize, key: Option<&[u8]>, path: P, use_mmap: bool) -> eyre::Result<Vec<u8>>
+where
+    P: AsRef<Path>,
+{
+    let file = File::open(path)?;
+    if use_mmap {
+        if let Some(digest) = try_hash_mmap(algorithm, length, key, &file)? {
+            return Ok(digest);
+        }
     }
 
-    let filename = &line[2..];
+    hash_reader(algorithm, length, key, BufReader::new(file))
+}
+
+/// Build the rayon thread pool used to hash multiple files concurrently.
+/// `num_threads == 0` defers to rayon's own default (the number of CPUs).
+fn build_thread_pool(num_threads: usize) -> eyre::Result<rayon::ThreadPool> {
+    Ok(rayon::ThreadPoolBuilder::new().num_threads(num_threads).build()?)
+}
+
+/// Tallies of a `--check` run, used to print the GNU-style trailing summary
+/// and to decide the process exit code.
+#[derive(Debug, Default)]
+struct CheckSummary {
+    attempted: usize,
+    malformed_lines: usize,
+    unreadable_files: usize,
+    ignored_missing: usize,
+    mismatched: usize,
+}
+
+impl CheckSummary {
+    fn has_errors(&self, strict: bool) -> bool {
+        self.mismatched > 0 || self.unreadable_files > 0 || (strict && self.malformed_lines > 0)
+    }
 
-    Ok((hash, filename))
+    /// Whether every checksum line named a file that was skipped because it
+    /// was missing and `--ignore-missing` was given.
+    fn all_missing(&self) -> bool {
+        self.attempted > 0 && self.ignored_missing == self.attempted
+    }
 }
 
-fn check_input<R>(args: &Args, check_filename: &str, reader: R) -> eyre::Result<bool>
+fn check_input<R>(
+    args: &Args,
+    algorithm: HashAlgorithm,
+    key: Option<&[u8]>,
+    check_filename: &str,
+    mut reader: R,
+) -> eyre::Result<CheckSummary>
 where
     R: BufRead,
 {
     let print_result = !(args.flag_quiet || args.flag_status);
-    let mut errors = false;
+    let mut summary = CheckSummary::default();
+    let mut raw_line = Vec::new();
+    let mut line_no = 0usize;
+
+    loop {
+        raw_line.clear();
+        if reader.read_until(b'\n', &mut raw_line)? == 0 {
+            break;
+        }
 
-    for (i, line) in reader.lines().enumerate() {
-        let line = line?;
-        let line = line.trim();
-        if line.starts_with('#') {
+        line_no += 1;
+        let line = trim_bytes(&raw_line);
+        if line.first() == Some(&b'#') {
             continue;
         }
 
-        let (hash, filename) = match split_check_line(line) {
+        let (hash, filename) = match split_check_line(line, algorithm) {
             Ok((hash, filename)) => (hash, filename),
             Err(e) => {
-                if args.flag_strict {
-                    errors = true;
-                }
+                summary.malformed_lines += 1;
 
                 if args.flag_warn {
-                    println!("{}:{}: {}", check_filename, i + 1, e)
+                    println!("{}:{}: {}", check_filename, line_no, e)
                 }
 
                 continue;
             }
         };
 
-        let length = hash.len() / 2;
-        let calculated_hash = match hash_file(length, filename) {
+        summary.attempted += 1;
+        let display_name = escape_filename(&filename).1;
+        let length = hash.len();
+        let calculated_hash = match hash_file(algorithm, length, key, &filename) {
             Ok(h) => h,
             Err(e) => {
                 if let Some(io_err) = e.downcast_ref::<io::Error>() {
                     if io_err.kind() == io::ErrorKind::NotFound && args.flag_ignore_missing {
+                        summary.ignored_missing += 1;
                         continue;
                     }
                 }
 
-                errors = true;
+                summary.unreadable_files += 1;
                 if !args.flag_status {
-                    println!("{}: FAILED {}", filename, e);
+                    io::Write::write_all(&mut io::stdout(), &display_name)?;
+                    println!(": FAILED {}", e);
                 }
 
                 continue;
@@ -158,56 +295,131 @@ where
 
         let matched = hash == calculated_hash;
         if !matched {
-            errors = true;
+            summary.mismatched += 1;
         }
 
         if print_result {
-            print!("{}: ", filename);
+            io::Write::write_all(&mut io::stdout(), &display_name)?;
             if matched {
-                println!("OK");
+                println!(": OK");
             } else {
-                println!("FAILED");
+                println!(": FAILED");
             }
         }
     }
 
-    Ok(errors)
+    Ok(summary)
+}
+
+fn print_check_warning(message: &str) {
+    eprintln!("b2sum: WARNING: {}", message);
+}
+
+fn report_check_summary(args: &Args, summary: &CheckSummary) {
+    if args.flag_status {
+        return;
+    }
+
+    if summary.malformed_lines > 0 {
+        let noun = if summary.malformed_lines == 1 { "line is" } else { "lines are" };
+        print_check_warning(&format!("{} {} improperly formatted", summary.malformed_lines, noun));
+    }
+
+    if summary.unreadable_files > 0 {
+        let noun = if summary.unreadable_files == 1 { "file" } else { "files" };
+        print_check_warning(&format!(
+            "{} listed {} could not be read",
+            summary.unreadable_files, noun
+        ));
+    }
+
+    if summary.mismatched > 0 {
+        let noun = if summary.mismatched == 1 { "checksum" } else { "checksums" };
+        print_check_warning(&format!("{} computed {} did NOT match", summary.mismatched, noun));
+    }
 }
 
 fn check_args(args: Args) -> eyre::Result<i32> {
+    let algorithm: HashAlgorithm = args.flag_algorithm.parse()?;
+    let key = resolve_key(&args, algorithm)?;
     let filename = args.arg_filename[0].as_str();
-    let errors = if filename == "-" {
+    let summary = if filename == "-" {
         let stdin = io::stdin();
-        check_input(&args, filename, stdin.lock())?
+        check_input(&args, algorithm, key.as_deref(), filename, stdin.lock())?
     } else {
         let file = File::open(filename)?;
         let reader = BufReader::new(file);
-        check_input(&args, filename, reader)?
+        check_input(&args, algorithm, key.as_deref(), filename, reader)?
     };
 
-    let code = if errors { 1 } else { 0 };
+    report_check_summary(&args, &summary);
+
+    if args.flag_ignore_missing && summary.all_missing() {
+        if !args.flag_status {
+            eprintln!("b2sum: {}: no file was verified", filename);
+        }
+
+        return Ok(1);
+    }
+
+    let code = if summary.has_errors(args.flag_strict) { 1 } else { 0 };
     Ok(code)
 }
 
 fn hash_args(args: Args) -> eyre::Result<i32> {
-    let length = args.flag_length / 8;
-    for filename in args.arg_filename {
-        let hash = if filename == "-" {
-            let stdin = io::stdin();
-            hash_reader(length, stdin.lock())?
-        } else {
-            hash_file(length, &filename)?
-        };
+    if args.flag_binary && args.flag_text {
+        bail!("the --binary and --text options are mutually exclusive");
+    }
 
+    let algorithm: HashAlgorithm = args.flag_algorithm.parse()?;
+    let length = digest_length(algorithm, args.flag_length)?;
+    let key = resolve_key(&args, algorithm)?;
+    let use_mmap = !args.flag_no_mmap;
+    let pool = build_thread_pool(args.flag_num_threads)?;
+
+    let hashes: Vec<eyre::Result<Vec<u8>>> = pool.install(|| {
+        args.arg_filename
+            .par_iter()
+            .map(|filename| {
+                if filename == "-" {
+                    let stdin = io::stdin();
+                    hash_reader(algorithm, length, key.as_deref(), stdin.lock())
+                } else {
+                    hash_path(algorithm, length, key.as_deref(), filename, use_mmap)
+                }
+            })
+            .collect()
+    });
+
+    for (filename, hash) in args.arg_filename.iter().zip(hashes) {
+        let hash = hash?;
+        let encoded = if args.flag_base64 { algorithm::to_base64(&hash) } else { algorithm::to_hex(&hash) };
+        let (needs_escape, display_name) = escape_filename(OsStr::new(filename));
         if args.flag_tag {
-            print!("BLAKE2b");
-            if args.flag_length < 512 {
-                print!("-{}", args.flag_length);
+            if needs_escape {
+                print!("\\");
+            }
+
+            print!("{}", algorithm.label());
+            if key.is_some() {
+                print!("-keyed");
+            }
+            if length < algorithm.max_length() {
+                print!("-{}", length * 8);
             }
 
-            println!(" ({}) = {}", filename, hash);
+            print!(" (");
+            io::Write::write_all(&mut io::stdout(), &display_name)?;
+            println!(") = {}", encoded);
         } else {
-            println!("{}  {}", hash, filename);
+            if needs_escape {
+                print!("\\");
+            }
+
+            let marker = if args.flag_binary { '*' } else { ' ' };
+            print!("{} {}", encoded, marker);
+            io::Write::write_all(&mut io::stdout(), &display_name)?;
+            println!();
         }
     }
 
@@ -235,54 +447,98 @@ mod tests {
     use super::*;
 
     #[test]
-    fn split_check_line_with_valid_line() {
-        let line = "c0ae24f806df19d850565b234bc37afd5035e7536388290db9413c98578394313f38b093143ecfbc208425d54b9bfef0d9917a9e93910f7914a97e73fea23534  test";
-        let (hash, filename) = split_check_line(line).unwrap();
-        assert_eq!(
-            "c0ae24f806df19d850565b234bc37afd5035e7536388290db9413c98578394313f38b093143ecfbc208425d54b9bfef0d9917a9e93910f7914a97e73fea23534",
-            hash
-        );
-        assert_eq!("test", filename);
+    fn test_hash_formatting() {
+        let expected = "7ea59e7a000ec003846b6607dfd5f9217b681dc1a81b0789b464c3995105d93083f7f0a86fca01a1bed27e9f9303ae58d01746e3b20443480bea56198e65bfc5";
+        let digest = hash_reader(HashAlgorithm::Blake2b, 64, None, "hi\n".as_bytes()).unwrap();
+        assert_eq!(expected, algorithm::to_hex(&digest));
     }
 
     #[test]
-    fn split_check_line_with_truncated_line() {
-        let line = "c0ae24f806df19d850565b234bc37afd5035e7536388290db9413c98578394313f38b093143ecfbc208425d54b9bfef0d9917a9e93910f7914a97e73fea23534 ";
-        let result = split_check_line(line).unwrap_err();
-        assert_eq!("Malformed line", result.to_string());
+    fn test_keyed_hash_formatting() {
+        let unkeyed = hash_reader(HashAlgorithm::Blake2b, 64, None, "hi\n".as_bytes()).unwrap();
+        let keyed = hash_reader(HashAlgorithm::Blake2b, 64, Some(b"secret"), "hi\n".as_bytes()).unwrap();
+        assert_ne!(unkeyed, keyed);
+    }
+
+    fn write_temp_file(name: &str, data: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("b2sum_test_{}_{}", process::id(), name));
+        std::fs::write(&path, data).unwrap();
+        path
     }
 
     #[test]
-    fn split_check_line_with_missing_filename() {
-        let line = "c0ae24f806df19d850565b234bc37afd5035e7536388290db9413c98578394313f38b093143ecfbc208425d54b9bfef0d9917a9e93910f7914a97e73fea23534  ";
-        let result = split_check_line(line).unwrap_err();
-        assert_eq!("Malformed line", result.to_string());
+    fn hash_path_with_mmap_matches_streaming_reader() {
+        let data = vec![0x5au8; MMAP_MIN_SIZE as usize + 1024];
+        let path = write_temp_file("mmap_above_threshold", &data);
+
+        let streamed = hash_reader(HashAlgorithm::Blake2b, 64, None, data.as_slice()).unwrap();
+        let mapped = hash_path(HashAlgorithm::Blake2b, 64, None, &path, true).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(streamed, mapped);
     }
 
     #[test]
-    fn split_check_line_with_too_small_hash() {
-        let line = "c  test";
-        let result = split_check_line(line).unwrap_err();
-        assert_eq!("Invalid hash length: 1", result.to_string());
+    fn hash_path_below_mmap_threshold_falls_back_to_streaming() {
+        let data = vec![0x5au8; 1024];
+        let path = write_temp_file("below_threshold", &data);
+
+        let streamed = hash_reader(HashAlgorithm::Blake2b, 64, None, data.as_slice()).unwrap();
+        let mapped = hash_path(HashAlgorithm::Blake2b, 64, None, &path, true).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(streamed, mapped);
     }
 
     #[test]
-    fn split_check_line_with_too_long_hash() {
-        let line = "c0ae24f806df19d850565b234bc37afd5035e7536388290db9413c98578394313f38b093143ecfbc208425d54b9bfef0d9917a9e93910f7914a97e73fea2353400  test";
-        let result = split_check_line(line).unwrap_err();
-        assert_eq!("Invalid hash length: 130", result.to_string());
+    fn test_base64_formatting() {
+        let digest = hash_reader(HashAlgorithm::Blake2b, 64, None, "hi\n".as_bytes()).unwrap();
+        assert_eq!(88, algorithm::to_base64(&digest).len());
     }
 
     #[test]
-    fn split_check_line_with_non_even_hash() {
-        let line = "c0ae0  test";
-        let result = split_check_line(line).unwrap_err();
-        assert_eq!("Invalid hash length: 5", result.to_string());
+    fn check_summary_malformed_only_is_not_an_error_unless_strict() {
+        let summary = CheckSummary {
+            malformed_lines: 2,
+            ..CheckSummary::default()
+        };
+
+        assert!(!summary.has_errors(false));
+        assert!(summary.has_errors(true));
     }
 
     #[test]
-    fn test_hash_formatting() {
-        let expected = "7ea59e7a000ec003846b6607dfd5f9217b681dc1a81b0789b464c3995105d93083f7f0a86fca01a1bed27e9f9303ae58d01746e3b20443480bea56198e65bfc5";
-        assert_eq!(expected, hash_reader(64, "hi\n".as_bytes()).unwrap());
+    fn check_summary_mismatched_is_always_an_error() {
+        let summary = CheckSummary {
+            attempted: 1,
+            mismatched: 1,
+            ..CheckSummary::default()
+        };
+
+        assert!(summary.has_errors(false));
+        assert!(!summary.all_missing());
+    }
+
+    #[test]
+    fn check_summary_all_missing_when_every_attempt_was_ignored() {
+        let summary = CheckSummary {
+            attempted: 2,
+            ignored_missing: 2,
+            ..CheckSummary::default()
+        };
+
+        assert!(summary.all_missing());
+        assert!(!summary.has_errors(true));
+    }
+
+    #[test]
+    fn check_summary_not_all_missing_when_some_attempts_succeeded() {
+        let summary = CheckSummary {
+            attempted: 2,
+            ignored_missing: 1,
+            ..CheckSummary::default()
+        };
+
+        assert!(!summary.all_missing());
     }
 }